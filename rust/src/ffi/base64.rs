@@ -15,9 +15,12 @@
  * 02110-1301, USA.
  */
 
-use crate::utils::base64::{decode_rfc2045, decode_rfc4648, get_decoded_buffer_size, Decoder};
+use crate::utils::base64::{
+    crypt_decode, crypt_encode, decode_rfc2045, decode_rfc4648, get_decoded_buffer_size,
+    standard_alphabet_value, Decoder, DecoderMode, CRYPT_ALPHABET,
+};
 use base64::{
-    engine::general_purpose::{STANDARD, STANDARD_NO_PAD},
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD},
     Engine,
 };
 use libc::c_ulong;
@@ -73,6 +76,46 @@ pub enum SCBase64Mode {
 
     /// Standard base64 with optional padding: decode only.
     SCBase64ModePadOpt,
+
+    /// URL and filename safe base64 (`-` and `_` in place of `+` and
+    /// `/`), with padding.
+    SCBase64ModeUrlSafe,
+
+    /// URL and filename safe base64 without padding.
+    SCBase64ModeUrlSafeNoPad,
+
+    /// The `crypt(3)` alphabet (`./0-9A-Za-z`), unpadded. Also packs
+    /// bits in crypt(3)'s own little-endian, least-significant-group-
+    /// first order rather than standard base64's order.
+    SCBase64ModeCrypt,
+
+    /// The bcrypt alphabet (`./A-Za-z0-9`, a different ordering than
+    /// `crypt(3)`), unpadded. Bit-packing is standard RFC 4648 order.
+    SCBase64ModeBcrypt,
+
+    /// The SHA-crypt alphabet, unpadded. Same alphabet and bit-packing
+    /// order as `SCBase64ModeCrypt`.
+    SCBase64ModeShacrypt,
+}
+
+/// The bcrypt alphabet: the same 64 characters as
+/// `crate::utils::base64::CRYPT_ALPHABET` but in a different order.
+/// Unlike crypt(3)/SHA-crypt, bcrypt packs its bits the same
+/// big-endian, most-significant-group-first way standard base64 does,
+/// so a plain alphabet substitution is correct here.
+const BCRYPT_ALPHABET: &str =
+    "./ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Build a `GeneralPurpose` engine for the bcrypt alphabet. Padding is
+/// treated as optional on decode, matching the unpadded output bcrypt
+/// always produces.
+fn password_hash_engine(alphabet: &str) -> base64::engine::GeneralPurpose {
+    let alphabet = base64::alphabet::Alphabet::new(alphabet)
+        .expect("password hash alphabet is a valid, fixed 64-character string");
+    let config = base64::engine::GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent);
+    base64::engine::GeneralPurpose::new(&alphabet, config)
 }
 
 #[no_mangle]
@@ -97,16 +140,35 @@ pub unsafe extern "C" fn SCBase64Decode(
     let in_vec = build_slice!(input, len);
     let out_vec = std::slice::from_raw_parts_mut(output, len);
     let mut num_decoded: u32 = 0;
-    let mut decoder = Decoder::new();
+    let mut num_consumed: usize = 0;
     match mode {
         SCBase64Mode::SCBase64ModeRFC2045 => {
-            if decode_rfc2045(&mut decoder, in_vec, out_vec, &mut num_decoded).is_err() {
+            let mut decoder = Decoder::new(DecoderMode::Rfc2045);
+            if decode_rfc2045(
+                &mut decoder,
+                in_vec,
+                out_vec,
+                &mut num_decoded,
+                &mut num_consumed,
+            )
+            .is_err()
+            {
                 debug_validate_bug_on!(num_decoded >= len as u32);
                 return num_decoded;
             }
         }
         SCBase64Mode::SCBase64ModeRFC4648 => {
-            if decode_rfc4648(&mut decoder, in_vec, out_vec, &mut num_decoded).is_err() {
+            let mut decoder = Decoder::new(DecoderMode::Rfc4648);
+            if decode_rfc4648(
+                &mut decoder,
+                in_vec,
+                out_vec,
+                &mut num_decoded,
+                &mut num_consumed,
+                standard_alphabet_value,
+            )
+            .is_err()
+            {
                 debug_validate_bug_on!(num_decoded >= len as u32);
                 return num_decoded;
             }
@@ -129,12 +191,141 @@ pub unsafe extern "C" fn SCBase64Decode(
                 num_decoded = decoded_len as u32;
             }
         }
+        SCBase64Mode::SCBase64ModeUrlSafe => {
+            if let Ok(decoded_len) = URL_SAFE.decode_slice(in_vec, out_vec) {
+                num_decoded = decoded_len as u32;
+            }
+        }
+        SCBase64Mode::SCBase64ModeUrlSafeNoPad => {
+            if let Ok(decoded_len) = URL_SAFE_NO_PAD.decode_slice(in_vec, out_vec) {
+                num_decoded = decoded_len as u32;
+            }
+        }
+        SCBase64Mode::SCBase64ModeCrypt | SCBase64Mode::SCBase64ModeShacrypt => {
+            if let Ok(decoded_len) = crypt_decode(in_vec, out_vec, CRYPT_ALPHABET) {
+                num_decoded = decoded_len as u32;
+            }
+        }
+        SCBase64Mode::SCBase64ModeBcrypt => {
+            if let Ok(decoded_len) =
+                password_hash_engine(BCRYPT_ALPHABET).decode_slice(in_vec, out_vec)
+            {
+                num_decoded = decoded_len as u32;
+            }
+        }
     }
 
     debug_validate_bug_on!(num_decoded >= len as u32);
     return num_decoded;
 }
 
+/// Allocate a persistent base64 decoder for streaming input, e.g. a
+/// MIME attachment body or a URL-safe token that arrives split across
+/// several packets.
+///
+/// The returned decoder must be released with `SCBase64DecoderFree`.
+/// `SCBase64ModeRFC2045` is decoded leniently, skipping whitespace and
+/// other non-alphabet bytes mid-stream; `SCBase64ModeRFC4648`,
+/// `SCBase64ModeStrict`, `SCBase64ModeNoPad` and `SCBase64ModePadOpt`
+/// are decoded through the standard alphabet, stopping at the first
+/// non-alphabet byte; `SCBase64ModeUrlSafe` and
+/// `SCBase64ModeUrlSafeNoPad` behave the same way through the URL- and
+/// filename-safe alphabet. The crypt(3)/SHA-crypt modes pack bits in a
+/// way this quantum-based decoder doesn't support streaming for (see
+/// `crate::utils::base64::crypt_decode`), and it has no bcrypt alphabet
+/// mode; passing any of these three modes returns a null pointer rather
+/// than silently mis-decoding.
+#[no_mangle]
+pub unsafe extern "C" fn SCBase64DecoderNew(mode: SCBase64Mode) -> *mut Decoder {
+    let decoder_mode = match mode {
+        SCBase64Mode::SCBase64ModeRFC2045 => DecoderMode::Rfc2045,
+        SCBase64Mode::SCBase64ModeRFC4648
+        | SCBase64Mode::SCBase64ModeStrict
+        | SCBase64Mode::SCBase64ModeNoPad
+        | SCBase64Mode::SCBase64ModePadOpt => DecoderMode::Rfc4648,
+        SCBase64Mode::SCBase64ModeUrlSafe | SCBase64Mode::SCBase64ModeUrlSafeNoPad => {
+            DecoderMode::Rfc4648UrlSafe
+        }
+        SCBase64Mode::SCBase64ModeCrypt
+        | SCBase64Mode::SCBase64ModeBcrypt
+        | SCBase64Mode::SCBase64ModeShacrypt => return std::ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(Decoder::new(decoder_mode)))
+}
+
+/// Feed another chunk of a base64 stream into a decoder allocated with
+/// `SCBase64DecoderNew`.
+///
+/// Up to 3 leftover base64 symbols from an incomplete quantum are
+/// retained across calls, so a 4-symbol group straddling a chunk
+/// boundary is still decoded correctly. `output` must be able to hold
+/// at least `out_cap` bytes; the number of bytes written is returned
+/// through `num_decoded`, and the number of leading `input` bytes that
+/// were consumed is returned through `num_consumed`.
+///
+/// On `SC_BASE64_OVERFLOW`, `num_consumed` stops short of `len`: the
+/// byte at `input[*num_consumed]` was rejected to avoid overflowing
+/// `output` and was *not* consumed. To resume a bounded-memory stream
+/// after an overflow, drain or grow `output` and retry this call with
+/// `input` advanced by `*num_consumed` bytes -- retrying with the
+/// original, unadvanced `input` re-decodes already-consumed bytes, and
+/// discarding the unconsumed remainder loses part of the stream.
+#[no_mangle]
+pub unsafe extern "C" fn SCBase64DecodeUpdate(
+    decoder: *mut Decoder, input: *const u8, len: usize, output: *mut u8, out_cap: usize,
+    num_decoded: *mut u32, num_consumed: *mut usize,
+) -> SCBase64ReturnCode {
+    if decoder.is_null()
+        || input.is_null()
+        || output.is_null()
+        || num_decoded.is_null()
+        || num_consumed.is_null()
+    {
+        return SCBase64ReturnCode::SC_BASE64_INVALID_ARG;
+    }
+    let decoder = &mut *decoder;
+    let in_vec = build_slice!(input, len);
+    let out_vec = std::slice::from_raw_parts_mut(output, out_cap);
+    *num_decoded = 0;
+    *num_consumed = 0;
+    if decoder
+        .update(in_vec, out_vec, &mut *num_decoded, &mut *num_consumed)
+        .is_err()
+    {
+        return SCBase64ReturnCode::SC_BASE64_OVERFLOW;
+    }
+    SCBase64ReturnCode::SC_BASE64_OK
+}
+
+/// Flush the trailing partial quantum, if any, left in a decoder
+/// allocated with `SCBase64DecoderNew` once the stream has ended.
+///
+/// Returns `SC_BASE64_INVALID_ARG` if the leftover symbols form an
+/// incomplete, unpadded quantum that cannot be decoded.
+#[no_mangle]
+pub unsafe extern "C" fn SCBase64DecodeFinalize(
+    decoder: *mut Decoder, output: *mut u8, out_cap: usize, num_decoded: *mut u32,
+) -> SCBase64ReturnCode {
+    if decoder.is_null() || output.is_null() || num_decoded.is_null() {
+        return SCBase64ReturnCode::SC_BASE64_INVALID_ARG;
+    }
+    let decoder = &mut *decoder;
+    let out_vec = std::slice::from_raw_parts_mut(output, out_cap);
+    *num_decoded = 0;
+    if decoder.finalize(out_vec, &mut *num_decoded).is_err() {
+        return SCBase64ReturnCode::SC_BASE64_INVALID_ARG;
+    }
+    SCBase64ReturnCode::SC_BASE64_OK
+}
+
+/// Release a decoder allocated with `SCBase64DecoderNew`.
+#[no_mangle]
+pub unsafe extern "C" fn SCBase64DecoderFree(decoder: *mut Decoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
 /// Base64 encode a buffer with a provided mode.
 ///
 /// This method exposes the Rust base64 encoder to C and should not be called from
@@ -154,6 +345,12 @@ pub unsafe extern "C" fn SCBase64EncodeWithMode(
     let input = std::slice::from_raw_parts(input, input_len as usize);
     let encoded = match mode {
         SCBase64Mode::SCBase64ModeNoPad => STANDARD_NO_PAD.encode(input),
+        SCBase64Mode::SCBase64ModeUrlSafe => URL_SAFE.encode(input),
+        SCBase64Mode::SCBase64ModeUrlSafeNoPad => URL_SAFE_NO_PAD.encode(input),
+        SCBase64Mode::SCBase64ModeCrypt | SCBase64Mode::SCBase64ModeShacrypt => {
+            crypt_encode(input, CRYPT_ALPHABET)
+        }
+        SCBase64Mode::SCBase64ModeBcrypt => password_hash_engine(BCRYPT_ALPHABET).encode(input),
         _ => STANDARD.encode(input),
     };
     if encoded.len() + 1 > *output_len as usize {
@@ -194,3 +391,65 @@ pub unsafe extern "C" fn SCBase64Encode(
 pub extern "C" fn SCBase64EncodeBufferSize(len: c_ulong) -> c_ulong {
     (4 * ((len) + 2) / 3) + 1
 }
+
+/// Base64 encode a buffer, wrapping the output every `line_length`
+/// characters, as required for MIME (RFC 2045, ≤ 76 chars/line) and
+/// PEM output.
+///
+/// `line_length` of 0 disables wrapping. `use_crlf` selects `\r\n`
+/// rather than `\n` as the line separator. When wrapping, every line,
+/// including the last, is newline-terminated. The output parameter
+/// must be an allocated buffer of at least the size returned by
+/// `SCBase64EncodeBufferSizeWrapped` for the same arguments, and this
+/// length must be provided in the output_len variable.
+#[no_mangle]
+pub unsafe extern "C" fn SCBase64EncodeWrapped(
+    input: *const u8, input_len: c_ulong, output: *mut c_uchar, output_len: *mut c_ulong,
+    line_length: c_ulong, use_crlf: bool,
+) -> SCBase64ReturnCode {
+    if input.is_null() || output.is_null() || output_len.is_null() {
+        return SCBase64ReturnCode::SC_BASE64_INVALID_ARG;
+    }
+    let input = std::slice::from_raw_parts(input, input_len as usize);
+    let encoded = STANDARD.encode(input);
+    let newline = if use_crlf { "\r\n" } else { "\n" };
+
+    let mut wrapped = String::with_capacity(encoded.len());
+    if line_length == 0 {
+        wrapped.push_str(&encoded);
+    } else {
+        // Every line, including the last, is newline-terminated, matching
+        // real MIME/PEM output and the allocation `SCBase64EncodeBufferSizeWrapped`
+        // reserves for it.
+        for chunk in encoded.as_bytes().chunks(line_length as usize) {
+            wrapped.push_str(std::str::from_utf8(chunk).expect("base64 output is ASCII"));
+            wrapped.push_str(newline);
+        }
+    }
+
+    if wrapped.len() + 1 > *output_len as usize {
+        return SCBase64ReturnCode::SC_BASE64_OVERFLOW;
+    }
+    let output = std::slice::from_raw_parts_mut(&mut *output, *output_len as usize);
+    output[0..wrapped.len()].copy_from_slice(wrapped.as_bytes());
+    output[wrapped.len()] = 0;
+    *output_len = wrapped.len() as c_ulong;
+    SCBase64ReturnCode::SC_BASE64_OK
+}
+
+/// Buffer size required by `SCBase64EncodeWrapped` for `len` input
+/// bytes: the plain encoded size, plus roughly
+/// `ceil(encoded_len / line_length)` newlines of `use_crlf`-dependent
+/// width, plus a byte for the NUL terminator.
+#[no_mangle]
+pub extern "C" fn SCBase64EncodeBufferSizeWrapped(
+    len: c_ulong, line_length: c_ulong, use_crlf: bool,
+) -> c_ulong {
+    let encoded_len = 4 * ((len) + 2) / 3;
+    if line_length == 0 {
+        return encoded_len + 1;
+    }
+    let newline_width: c_ulong = if use_crlf { 2 } else { 1 };
+    let lines = (encoded_len + line_length - 1) / line_length;
+    encoded_len + lines * newline_width + 1
+}
@@ -0,0 +1,506 @@
+/* Copyright (C) 2021-2024 Open Information Security Foundation
+ *
+ * You can copy, redistribute or modify this Program under the terms of
+ * the GNU General Public License version 2 as published by the Free
+ * Software Foundation.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * version 2 along with this program; if not, write to the Free Software
+ * Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA
+ * 02110-1301, USA.
+ */
+
+//! Base64 decoding helpers backing `crate::ffi::base64`.
+//!
+//! The `base64` crate's `Engine` implementations are a good fit for the
+//! strict RFC 4648 modes, but Suricata also needs the more forgiving
+//! RFC 2045 behaviour (skip anything that isn't in the alphabet) which
+//! the crate doesn't implement directly, so that variant is decoded by
+//! hand here, one symbol at a time, through a small persistent `Decoder`.
+
+/// Map a single standard base64 alphabet character to its 6-bit value.
+#[inline]
+pub(crate) fn standard_alphabet_value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Map a single URL- and filename-safe base64 alphabet character (RFC
+/// 4648 section 5) to its 6-bit value: the same as [`standard_alphabet_value`]
+/// except `-` and `_` stand in for `+` and `/`.
+#[inline]
+fn url_safe_alphabet_value(c: u8) -> Option<u8> {
+    match c {
+        b'-' => Some(62),
+        b'_' => Some(63),
+        _ => standard_alphabet_value(c),
+    }
+}
+
+/// Which RFC base64 decoding behaviour a [`Decoder`] follows.
+///
+/// Only alphabets whose symbols pack into bytes the same big-endian,
+/// most-significant-group-first way as standard base64 can use this
+/// quantum-based decoder; crypt(3)/SHA-crypt's little-endian packing
+/// (see [`crypt_decode`]) needs a different algorithm entirely and so
+/// has no streaming `Decoder` support.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecoderMode {
+    /// Skip anything outside of the alphabet, including whitespace
+    /// (RFC 2045).
+    Rfc2045,
+    /// Stop at the first character outside of the alphabet (RFC 4648).
+    Rfc4648,
+    /// Like `Rfc4648`, but through the URL- and filename-safe alphabet
+    /// (`-` and `_` in place of `+` and `/`).
+    Rfc4648UrlSafe,
+}
+
+/// Holds the base64 symbols of a not-yet-complete 4-symbol quantum.
+///
+/// A single call to [`decode_rfc2045`] or [`decode_rfc4648`] may end in
+/// the middle of a quantum; keeping the partial state here is what lets
+/// the same `Decoder` be reused across multiple calls, e.g. to decode a
+/// base64 body as it streams in across several packets.
+#[derive(Debug)]
+pub struct Decoder {
+    mode: DecoderMode,
+    quantum: [u8; 4],
+    quantum_len: usize,
+}
+
+impl Decoder {
+    pub fn new(mode: DecoderMode) -> Self {
+        Self {
+            mode,
+            quantum: [0; 4],
+            quantum_len: 0,
+        }
+    }
+
+    /// Feed another chunk of input through the decoder, picking up any
+    /// leftover quantum symbols from a previous call to `update`.
+    ///
+    /// `num_consumed` is always set to the number of leading bytes of
+    /// `input` that were consumed, even on error: on `Err(())`, that is
+    /// the offset of the byte that triggered the overflow, which was
+    /// *not* consumed, so a caller that grows or drains `output` should
+    /// retry starting from `input[*num_consumed..]` rather than from the
+    /// beginning of `input` again.
+    pub fn update(
+        &mut self, input: &[u8], output: &mut [u8], num_decoded: &mut u32,
+        num_consumed: &mut usize,
+    ) -> Result<(), ()> {
+        match self.mode {
+            DecoderMode::Rfc2045 => decode_rfc2045(self, input, output, num_decoded, num_consumed),
+            DecoderMode::Rfc4648 => decode_rfc4648(
+                self,
+                input,
+                output,
+                num_decoded,
+                num_consumed,
+                standard_alphabet_value,
+            ),
+            DecoderMode::Rfc4648UrlSafe => decode_rfc4648(
+                self,
+                input,
+                output,
+                num_decoded,
+                num_consumed,
+                url_safe_alphabet_value,
+            ),
+        }
+    }
+
+    /// Flush whatever partial quantum is left pending after the last
+    /// `update` call.
+    ///
+    /// A trailing quantum of 2 or 3 symbols is a valid, if unpadded,
+    /// ending and is decoded as-is. A single leftover symbol cannot
+    /// have come from a well-formed base64 stream and is an error, as is
+    /// a leftover of 4: `push` always drains a full quantum as soon as
+    /// it forms, so `quantum_len` should never reach 4 here; treat it as
+    /// an error defensively rather than silently dropping it.
+    pub fn finalize(&mut self, output: &mut [u8], num_decoded: &mut u32) -> Result<(), ()> {
+        match self.quantum_len {
+            0 => Ok(()),
+            2 | 3 => self.flush_short(output, num_decoded),
+            _ => Err(()),
+        }
+    }
+
+    /// Push a decoded 6-bit value, emitting a 3-byte group to `output`
+    /// once 4 symbols have accumulated.
+    ///
+    /// On overflow, `value` is rejected and `quantum_len` is left
+    /// unchanged (never advanced to 4) so the decoder stays in a valid
+    /// state and a later call can't index `quantum` out of bounds.
+    fn push(&mut self, value: u8, output: &mut [u8], num_decoded: &mut u32) -> Result<(), ()> {
+        if self.quantum_len == 3 {
+            let offset = *num_decoded as usize;
+            if offset + 3 > output.len() {
+                return Err(());
+            }
+            let q = &mut self.quantum;
+            q[3] = value;
+            output[offset] = (q[0] << 2) | (q[1] >> 4);
+            output[offset + 1] = (q[1] << 4) | (q[2] >> 2);
+            output[offset + 2] = (q[2] << 6) | q[3];
+            *num_decoded += 3;
+            self.quantum_len = 0;
+        } else {
+            self.quantum[self.quantum_len] = value;
+            self.quantum_len += 1;
+        }
+        Ok(())
+    }
+
+    /// Flush a short (2 or 3 symbol) quantum as seen when padding is hit.
+    ///
+    /// A single leftover symbol carries fewer than 8 bits and cannot
+    /// produce a byte, so it is simply discarded.
+    fn flush_short(&mut self, output: &mut [u8], num_decoded: &mut u32) -> Result<(), ()> {
+        let offset = *num_decoded as usize;
+        match self.quantum_len {
+            2 => {
+                if offset + 1 > output.len() {
+                    return Err(());
+                }
+                let q = &self.quantum;
+                output[offset] = (q[0] << 2) | (q[1] >> 4);
+                *num_decoded += 1;
+            }
+            3 => {
+                if offset + 2 > output.len() {
+                    return Err(());
+                }
+                let q = &self.quantum;
+                output[offset] = (q[0] << 2) | (q[1] >> 4);
+                output[offset + 1] = (q[1] << 4) | (q[2] >> 2);
+                *num_decoded += 2;
+            }
+            _ => {}
+        }
+        self.quantum_len = 0;
+        Ok(())
+    }
+}
+
+/// Decode `input` into `output` per RFC 2045: anything outside of the
+/// base64 alphabet, including whitespace, is silently skipped, and a
+/// padding character flushes whatever partial quantum is pending.
+///
+/// `num_consumed` is set to `input.len()` on success, or to the offset
+/// of the byte that overflowed `output` on error; see [`Decoder::update`].
+///
+/// See the unittest `B64TestVectorsRFC2045` in src/util-base64.c.
+pub fn decode_rfc2045(
+    decoder: &mut Decoder, input: &[u8], output: &mut [u8], num_decoded: &mut u32,
+    num_consumed: &mut usize,
+) -> Result<(), ()> {
+    for (i, &byte) in input.iter().enumerate() {
+        if byte == b'=' {
+            if decoder.flush_short(output, num_decoded).is_err() {
+                *num_consumed = i;
+                return Err(());
+            }
+            continue;
+        }
+        if let Some(value) = standard_alphabet_value(byte) {
+            if decoder.push(value, output, num_decoded).is_err() {
+                *num_consumed = i;
+                return Err(());
+            }
+        }
+    }
+    *num_consumed = input.len();
+    Ok(())
+}
+
+/// Decode `input` into `output` per RFC 4648: decoding stops at the
+/// first character outside of `alphabet_value`'s alphabet, flushing a
+/// partial quantum first if that character is the padding character.
+///
+/// `num_consumed` is set to the number of bytes actually examined: all
+/// of `input` on success, the offset of the padding character plus one
+/// if decoding stopped there, the offset of the rejected character if
+/// decoding stopped because it fell outside the alphabet, or the offset
+/// of the byte that overflowed `output` on error; see [`Decoder::update`].
+///
+/// See the unittest `B64TestVectorsRFC4648` in src/util-base64.c.
+pub fn decode_rfc4648(
+    decoder: &mut Decoder, input: &[u8], output: &mut [u8], num_decoded: &mut u32,
+    num_consumed: &mut usize, alphabet_value: fn(u8) -> Option<u8>,
+) -> Result<(), ()> {
+    for (i, &byte) in input.iter().enumerate() {
+        if byte == b'=' {
+            if decoder.flush_short(output, num_decoded).is_err() {
+                *num_consumed = i;
+                return Err(());
+            }
+            *num_consumed = i + 1;
+            return Ok(());
+        }
+        match alphabet_value(byte) {
+            Some(value) => {
+                if decoder.push(value, output, num_decoded).is_err() {
+                    *num_consumed = i;
+                    return Err(());
+                }
+            }
+            None => {
+                *num_consumed = i;
+                return Ok(());
+            }
+        }
+    }
+    *num_consumed = input.len();
+    Ok(())
+}
+
+/// The decoded size of a base64 buffer is never larger than its encoded
+/// size, so callers can safely size the output buffer off the input
+/// length.
+pub fn get_decoded_buffer_size(input_len: u32) -> u32 {
+    input_len
+}
+
+/// The `crypt(3)` alphabet, used by traditional DES-crypt as well as the
+/// SHA-crypt hashes produced by glibc's `crypt()`. Unlike the bcrypt
+/// alphabet, which reuses standard RFC 4648 bit-packing with a
+/// substituted alphabet, crypt(3)/SHA-crypt also pack bits differently:
+/// see [`crypt_encode`]/[`crypt_decode`].
+pub const CRYPT_ALPHABET: &[u8; 64] =
+    b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Encode `input` using the crypt(3)/SHA-crypt base64 variant.
+///
+/// Unlike RFC 4648, which treats each 3-byte group as a big-endian
+/// 24-bit number and emits its 6-bit groups most-significant-first,
+/// crypt(3)/SHA-crypt (glibc's `to64()`/`b64_from_24bit`, used by
+/// `crypt_sha256_rn`/`crypt_sha512_rn`) treat the group as
+/// little-endian and emit 6-bit groups least-significant-first. A
+/// trailing group of 1 or 2 bytes still emits 2 or 3 symbols
+/// respectively, just like the standard algorithm, but with the bits
+/// reversed in the same way.
+pub fn crypt_encode(input: &[u8], alphabet: &[u8; 64]) -> String {
+    let mut out = String::with_capacity((input.len() * 4 + 2) / 3);
+    for chunk in input.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = *chunk.get(1).unwrap_or(&0) as u32;
+        let b2 = *chunk.get(2).unwrap_or(&0) as u32;
+        let mut w = b0 | (b1 << 8) | (b2 << 16);
+        let num_symbols = match chunk.len() {
+            1 => 2,
+            2 => 3,
+            _ => 4,
+        };
+        for _ in 0..num_symbols {
+            out.push(alphabet[(w & 0x3f) as usize] as char);
+            w >>= 6;
+        }
+    }
+    out
+}
+
+/// Decode `input` (produced by [`crypt_encode`] or a real crypt(3)/
+/// SHA-crypt hash) into `output`, mapping each symbol through
+/// `alphabet`'s index table and reassembling bytes with the same
+/// little-endian, least-significant-group-first packing `crypt_encode`
+/// uses. Returns the number of bytes written, or `Err(())` if a symbol
+/// isn't in `alphabet`, the input ends in a lone trailing symbol, or
+/// `output` is too small.
+pub fn crypt_decode(input: &[u8], output: &mut [u8], alphabet: &[u8; 64]) -> Result<usize, ()> {
+    let mut table = [u8::MAX; 256];
+    for (value, &byte) in alphabet.iter().enumerate() {
+        table[byte as usize] = value as u8;
+    }
+
+    let mut num_decoded = 0;
+    for chunk in input.chunks(4) {
+        let num_bytes = match chunk.len() {
+            2 => 1,
+            3 => 2,
+            4 => 3,
+            _ => return Err(()),
+        };
+        let mut w: u32 = 0;
+        for (i, &byte) in chunk.iter().enumerate() {
+            let value = table[byte as usize];
+            if value == u8::MAX {
+                return Err(());
+            }
+            w |= (value as u32) << (6 * i);
+        }
+        if num_decoded + num_bytes > output.len() {
+            return Err(());
+        }
+        for i in 0..num_bytes {
+            output[num_decoded + i] = ((w >> (8 * i)) & 0xff) as u8;
+        }
+        num_decoded += num_bytes;
+    }
+    Ok(num_decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoder_handles_quantum_straddling_update_calls() {
+        // "Zm9vYmFy" decodes to "foobar"; split so the second quantum's
+        // symbols are spread across three separate `update` calls.
+        let mut decoder = Decoder::new(DecoderMode::Rfc4648);
+        let mut output = [0u8; 6];
+        let mut num_decoded = 0u32;
+        let mut num_consumed = 0usize;
+
+        decoder
+            .update(b"Zm9", &mut output, &mut num_decoded, &mut num_consumed)
+            .unwrap();
+        decoder
+            .update(b"v", &mut output, &mut num_decoded, &mut num_consumed)
+            .unwrap();
+        decoder
+            .update(b"YmF", &mut output, &mut num_decoded, &mut num_consumed)
+            .unwrap();
+        decoder
+            .update(b"y", &mut output, &mut num_decoded, &mut num_consumed)
+            .unwrap();
+        decoder.finalize(&mut output, &mut num_decoded).unwrap();
+
+        assert_eq!(num_decoded, 6);
+        assert_eq!(&output, b"foobar");
+    }
+
+    #[test]
+    fn decoder_overflow_does_not_corrupt_state() {
+        // "YWJjZGVm" is two full quanta ("abc" + "def"); an output
+        // buffer with room for only the first quantum must overflow on
+        // the second without leaving the decoder unable to continue.
+        let mut decoder = Decoder::new(DecoderMode::Rfc4648);
+        let mut small_output = [0u8; 3];
+        let mut num_decoded = 0u32;
+        let mut num_consumed = 0usize;
+        let result = decoder.update(
+            b"YWJjZGVm",
+            &mut small_output,
+            &mut num_decoded,
+            &mut num_consumed,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(num_decoded, 3);
+        assert_eq!(&small_output, b"abc");
+        // "Z", "G" and "V" were already accumulated into the pending
+        // quantum (quantum_len only reaches 3, never overflowing on its
+        // own); it's the 4th symbol, "m" at offset 7, that would
+        // complete the quantum and overflow `output`, so it is the one
+        // left unconsumed for the caller to resume from.
+        assert_eq!(num_consumed, 7);
+
+        // Must not panic (e.g. by indexing quantum[4] on a [u8; 4]) and
+        // must decode correctly when resumed from the reported offset,
+        // rather than merging the retry's bytes into an unrelated
+        // quantum.
+        let mut output = [0u8; 16];
+        let mut num_decoded2 = 0u32;
+        let mut num_consumed2 = 0usize;
+        decoder
+            .update(
+                &b"YWJjZGVm"[num_consumed..],
+                &mut output,
+                &mut num_decoded2,
+                &mut num_consumed2,
+            )
+            .unwrap();
+        assert_eq!(&output[..num_decoded2 as usize], b"def");
+    }
+
+    #[test]
+    fn finalize_flushes_short_trailing_group() {
+        let mut decoder = Decoder::new(DecoderMode::Rfc4648);
+        let mut output = [0u8; 4];
+        let mut num_decoded = 0u32;
+        let mut num_consumed = 0usize;
+
+        // "Zm8" is 3 leftover symbols (no padding): a valid, if
+        // unpadded, ending that finalize must flush to 2 bytes.
+        decoder
+            .update(b"Zm8", &mut output, &mut num_decoded, &mut num_consumed)
+            .unwrap();
+        decoder.finalize(&mut output, &mut num_decoded).unwrap();
+
+        assert_eq!(&output[..num_decoded as usize], b"fo");
+    }
+
+    #[test]
+    fn finalize_rejects_lone_trailing_symbol() {
+        let mut decoder = Decoder::new(DecoderMode::Rfc4648);
+        let mut output = [0u8; 4];
+        let mut num_decoded = 0u32;
+        let mut num_consumed = 0usize;
+
+        decoder
+            .update(b"Z", &mut output, &mut num_decoded, &mut num_consumed)
+            .unwrap();
+        assert!(decoder.finalize(&mut output, &mut num_decoded).is_err());
+    }
+
+    #[test]
+    fn decoder_url_safe_mode_decodes_url_safe_alphabet() {
+        // A JWT-style segment using '-' and '_', which are not part of
+        // the standard alphabet the plain Rfc4648 mode recognizes.
+        let mut decoder = Decoder::new(DecoderMode::Rfc4648UrlSafe);
+        let mut output = [0u8; 8];
+        let mut num_decoded = 0u32;
+        let mut num_consumed = 0usize;
+
+        decoder
+            .update(b"-_78", &mut output, &mut num_decoded, &mut num_consumed)
+            .unwrap();
+        decoder.finalize(&mut output, &mut num_decoded).unwrap();
+
+        assert_eq!(num_consumed, 4);
+        assert_eq!(&output[..num_decoded as usize], &[0xfb, 0xfe, 0xfc]);
+    }
+
+    #[test]
+    fn crypt_encode_decode_roundtrip() {
+        for input in [
+            &b""[..],
+            &b"f"[..],
+            &b"fo"[..],
+            &b"foo"[..],
+            &b"foob"[..],
+            &b"fooba"[..],
+            &b"foobar"[..],
+        ] {
+            let encoded = crypt_encode(input, CRYPT_ALPHABET);
+            let mut output = [0u8; 16];
+            let num_decoded = crypt_decode(encoded.as_bytes(), &mut output, CRYPT_ALPHABET)
+                .unwrap_or_else(|_| panic!("failed to decode {encoded:?} for input {input:?}"));
+            assert_eq!(&output[..num_decoded], input);
+        }
+    }
+
+    #[test]
+    fn crypt_encode_matches_known_vector() {
+        // From glibc's to64()/b64_from_24bit packing: "foobar" encodes
+        // to "axqPW3aQ" in the crypt(3)/SHA-crypt alphabet, unlike
+        // standard base64's "Zm9vYmFy".
+        assert_eq!(crypt_encode(b"foobar", CRYPT_ALPHABET), "axqPW3aQ");
+    }
+}